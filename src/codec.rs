@@ -0,0 +1,217 @@
+//! Transparent (de)compression for Yaz0-wrapped assets and zstd-compressed
+//! `.rbp` patches.
+
+use std::io;
+use std::path::Path;
+
+const YAZ0_MAGIC: &[u8; 4] = b"Yaz0";
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xB5, 0x2F, 0xFD];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Container {
+    Raw,
+    Yaz0,
+    Zstd,
+}
+
+/// Sniffs a buffer's leading bytes for a known compression magic.
+pub(crate) fn detect(bytes: &[u8]) -> Container {
+    if bytes.starts_with(YAZ0_MAGIC) {
+        Container::Yaz0
+    } else if bytes.starts_with(&ZSTD_MAGIC) {
+        Container::Zstd
+    } else {
+        Container::Raw
+    }
+}
+
+/// Sniffs a file's container without reading it in full, so the cheap
+/// integrity pre-check on an uncompressed input doesn't have to buffer it.
+pub(crate) fn detect_file(path: &Path) -> io::Result<Container> {
+    use std::io::Read;
+    let mut file = std::fs::File::open(path)?;
+    let mut buf = [0u8; 16];
+    let mut len = 0;
+    while len < buf.len() {
+        match file.read(&mut buf[len..]) {
+            Ok(0) => break,
+            Ok(n) => len += n,
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(detect(&buf[..len]))
+}
+
+/// A (de)compression container, implemented per format.
+pub(crate) trait Codec {
+    fn decode(&self, bytes: &[u8]) -> io::Result<Vec<u8>>;
+    fn encode(&self, bytes: &[u8]) -> io::Result<Vec<u8>>;
+}
+
+pub(crate) struct RawCodec;
+
+impl Codec for RawCodec {
+    fn decode(&self, bytes: &[u8]) -> io::Result<Vec<u8>> {
+        Ok(bytes.to_vec())
+    }
+
+    fn encode(&self, bytes: &[u8]) -> io::Result<Vec<u8>> {
+        Ok(bytes.to_vec())
+    }
+}
+
+pub(crate) struct Yaz0Codec;
+
+impl Codec for Yaz0Codec {
+    fn decode(&self, bytes: &[u8]) -> io::Result<Vec<u8>> {
+        decode_yaz0(bytes)
+    }
+
+    fn encode(&self, _bytes: &[u8]) -> io::Result<Vec<u8>> {
+        Err(io::Error::other("re-encoding a Yaz0 container is not supported"))
+    }
+}
+
+pub(crate) struct ZstdCodec;
+
+impl Codec for ZstdCodec {
+    fn decode(&self, bytes: &[u8]) -> io::Result<Vec<u8>> {
+        zstd::decode_all(bytes)
+    }
+
+    fn encode(&self, bytes: &[u8]) -> io::Result<Vec<u8>> {
+        zstd::encode_all(bytes, 0)
+    }
+}
+
+/// Returns the codec implementing a previously [`detect`]ed container.
+pub(crate) fn for_container(container: Container) -> Box<dyn Codec> {
+    match container {
+        Container::Raw => Box::new(RawCodec),
+        Container::Yaz0 => Box::new(Yaz0Codec),
+        Container::Zstd => Box::new(ZstdCodec),
+    }
+}
+
+/// Detects and decodes a buffer in one step.
+pub(crate) fn decode(bytes: &[u8]) -> io::Result<Vec<u8>> {
+    for_container(detect(bytes)).decode(bytes)
+}
+
+/// Decodes a Yaz0 stream: a 16-byte header (`"Yaz0"`, big-endian decompressed
+/// size, 8 reserved bytes) followed by control-byte-prefixed groups of 8
+/// operations, each either a literal byte or a back-reference copy.
+fn decode_yaz0(data: &[u8]) -> io::Result<Vec<u8>> {
+    if data.len() < 16 || &data[0..4] != YAZ0_MAGIC {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "not a Yaz0 stream"));
+    }
+    let dest_size = u32::from_be_bytes([data[4], data[5], data[6], data[7]]) as usize;
+    let mut out = Vec::with_capacity(dest_size);
+    let mut pos = 16;
+    let mut group = 0u8;
+    let mut bits_left = 0u8;
+
+    let eof = || io::Error::new(io::ErrorKind::UnexpectedEof, "truncated Yaz0 stream");
+
+    while out.len() < dest_size {
+        if bits_left == 0 {
+            group = *data.get(pos).ok_or_else(eof)?;
+            pos += 1;
+            bits_left = 8;
+        }
+
+        if group & 0x80 != 0 {
+            out.push(*data.get(pos).ok_or_else(eof)?);
+            pos += 1;
+        } else {
+            let b0 = *data.get(pos).ok_or_else(eof)?;
+            let b1 = *data.get(pos + 1).ok_or_else(eof)?;
+            pos += 2;
+            let distance = (((b0 & 0x0F) as usize) << 8 | b1 as usize) + 1;
+            let length = match b0 >> 4 {
+                0 => {
+                    let extra = *data.get(pos).ok_or_else(eof)?;
+                    pos += 1;
+                    extra as usize + 0x12
+                }
+                n => n as usize + 2,
+            };
+            let start = out.len().checked_sub(distance).ok_or_else(|| {
+                io::Error::new(io::ErrorKind::InvalidData, "Yaz0 back-reference underflows output")
+            })?;
+            for k in 0..length {
+                let byte = out[start + k];
+                out.push(byte);
+            }
+        }
+
+        group <<= 1;
+        bits_left -= 1;
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detect_recognizes_each_magic() {
+        assert_eq!(detect(b"Yaz0rest"), Container::Yaz0);
+        assert_eq!(detect(&[0x28, 0xB5, 0x2F, 0xFD, 1, 2]), Container::Zstd);
+        assert_eq!(detect(b"plain data"), Container::Raw);
+    }
+
+    #[test]
+    fn decode_yaz0_expands_literals_and_backreferences() {
+        // Group byte 0xE0 (3 set bits) marks the first 3 ops as literals,
+        // followed by one back-reference copying 4 bytes from distance 3.
+        let mut data = vec![];
+        data.extend_from_slice(YAZ0_MAGIC);
+        data.extend_from_slice(&7u32.to_be_bytes());
+        data.extend_from_slice(&[0, 0, 0, 0, 0, 0, 0, 0]);
+        data.push(0xE0);
+        data.extend_from_slice(b"ABC");
+        data.extend_from_slice(&[0x20, 0x02]);
+        assert_eq!(decode_yaz0(&data).unwrap(), b"ABCABCA");
+    }
+
+    #[test]
+    fn decode_yaz0_handles_extended_length_backreferences() {
+        // Group byte 0x80 marks one literal followed by a backreference
+        // whose 0x0-nibble length means the real length is an extra byte
+        // plus 0x12; a distance-1 copy repeats the literal into a run.
+        let mut data = vec![];
+        data.extend_from_slice(YAZ0_MAGIC);
+        data.extend_from_slice(&22u32.to_be_bytes());
+        data.extend_from_slice(&[0, 0, 0, 0, 0, 0, 0, 0]);
+        data.push(0x80);
+        data.push(b'Z');
+        data.extend_from_slice(&[0x00, 0x00, 0x03]);
+        assert_eq!(decode_yaz0(&data).unwrap(), b"Z".repeat(22));
+    }
+
+    #[test]
+    fn decode_yaz0_rejects_truncated_and_non_magic_input() {
+        assert!(decode_yaz0(b"not a yaz0 stream at all").is_err());
+        let mut truncated = vec![];
+        truncated.extend_from_slice(YAZ0_MAGIC);
+        truncated.extend_from_slice(&4u32.to_be_bytes());
+        truncated.extend_from_slice(&[0, 0, 0, 0, 0, 0, 0, 0]);
+        truncated.push(0xFF); // claims 8 literals follow, but none do
+        assert!(decode_yaz0(&truncated).is_err());
+    }
+
+    #[test]
+    fn yaz0_encode_is_unsupported() {
+        assert!(Yaz0Codec.encode(b"anything").is_err());
+    }
+
+    #[test]
+    fn zstd_round_trips() {
+        let original = b"round trip me through zstd";
+        let encoded = ZstdCodec.encode(original).unwrap();
+        assert_eq!(ZstdCodec.decode(&encoded).unwrap(), original);
+    }
+}
@@ -0,0 +1,128 @@
+use std::fs::File;
+use std::hash::Hasher;
+use std::io::{self, Read};
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+use siphasher::sip128::{Hash128, Hasher128, SipHasher13};
+
+/// Size of the cheap "does this look like the same file" block hashed first.
+const PARTIAL_BLOCK_SIZE: usize = 4096;
+/// Block size used to stream the remainder of a file when hashing from disk.
+const READ_BLOCK_SIZE: usize = 64 * 1024;
+
+/// Source-file integrity fingerprint stored in a [`Patch`](crate::patch::Patch).
+///
+/// `partial` only covers the first [`PARTIAL_BLOCK_SIZE`] bytes so a wrong
+/// input file can be rejected without reading it in full; `full` is checked
+/// afterwards to confirm.
+#[derive(Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Debug, Default)]
+pub(crate) struct ContentHash {
+    pub partial: u128,
+    pub full: u128,
+}
+
+impl ContentHash {
+    /// Hashes an in-memory buffer, as used while building a patch when the
+    /// whole file is already loaded.
+    pub fn of_bytes(bytes: &[u8]) -> ContentHash {
+        let partial_len = bytes.len().min(PARTIAL_BLOCK_SIZE);
+        ContentHash {
+            partial: hash_slice(&bytes[..partial_len]),
+            full: hash_slice(bytes),
+        }
+    }
+
+    /// A patch file built before source/target hashing was added has no
+    /// `source_hash`/`target_hash` on disk and `#[serde(default)]` fills them
+    /// in as all-zero. Treat that as "unknown, don't verify" rather than
+    /// comparing against it, so patches predating this field keep applying.
+    pub fn is_unknown(&self) -> bool {
+        *self == ContentHash::default()
+    }
+}
+
+/// Hashes a byte buffer, as used for a produced patch target that we only
+/// ever need the full hash of.
+pub(crate) fn full_hash(bytes: &[u8]) -> u128 {
+    hash_slice(bytes)
+}
+
+/// Cheap pre-check: hashes only the first [`PARTIAL_BLOCK_SIZE`] bytes of a
+/// file on disk without buffering the rest.
+pub(crate) fn partial_hash_of_file(path: &Path) -> io::Result<u128> {
+    let mut file = File::open(path)?;
+    let mut buf = [0u8; PARTIAL_BLOCK_SIZE];
+    let len = read_fill(&mut file, &mut buf)?;
+    Ok(hash_slice(&buf[..len]))
+}
+
+/// Streams a file in fixed blocks and hashes it in full, so large files
+/// aren't buffered whole just to confirm their identity.
+pub(crate) fn full_hash_of_file(path: &Path) -> io::Result<u128> {
+    Ok(content_hash_of_file(path)?.full)
+}
+
+/// Computes both the partial and full hash of a file in a single streamed
+/// pass, without ever buffering it whole.
+pub(crate) fn content_hash_of_file(path: &Path) -> io::Result<ContentHash> {
+    let mut file = File::open(path)?;
+    let mut partial_buf = [0u8; PARTIAL_BLOCK_SIZE];
+    let partial_len = read_fill(&mut file, &mut partial_buf)?;
+    let partial = hash_slice(&partial_buf[..partial_len]);
+
+    let mut hasher = SipHasher13::new();
+    hasher.write(&partial_buf[..partial_len]);
+    let mut buf = [0u8; READ_BLOCK_SIZE];
+    loop {
+        let len = read_fill(&mut file, &mut buf)?;
+        if len == 0 {
+            break;
+        }
+        hasher.write(&buf[..len]);
+    }
+    Ok(ContentHash {
+        partial,
+        full: combine(hasher.finish128()),
+    })
+}
+
+fn hash_slice(bytes: &[u8]) -> u128 {
+    let mut hasher = SipHasher13::new();
+    hasher.write(bytes);
+    combine(hasher.finish128())
+}
+
+fn combine(hash: Hash128) -> u128 {
+    (u128::from(hash.h1) << 64) | u128::from(hash.h2)
+}
+
+/// Reads until `buf` is full or the file is exhausted, retrying on
+/// interrupted reads.
+fn read_fill(file: &mut File, buf: &mut [u8]) -> io::Result<usize> {
+    let mut total = 0;
+    while total < buf.len() {
+        match file.read(&mut buf[total..]) {
+            Ok(0) => break,
+            Ok(n) => total += n,
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(total)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn default_hash_is_unknown() {
+        assert!(ContentHash::default().is_unknown());
+    }
+
+    #[test]
+    fn computed_hash_is_not_unknown() {
+        assert!(!ContentHash::of_bytes(b"some bytes").is_unknown());
+    }
+}
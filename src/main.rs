@@ -1,13 +1,19 @@
 #![warn(clippy::all)]
 use std::fs;
-use std::path::PathBuf;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
 
+use aho_corasick::AhoCorasick;
 use clap::arg_enum;
 use snafu::{ResultExt, Snafu};
 use structopt::StructOpt;
 
+mod codec;
+mod hash;
+mod myers;
 mod patch;
 
+use codec::Codec;
 use patch::{Patch, PatchSection};
 
 arg_enum! {
@@ -40,6 +46,19 @@ struct Options {
     only_char: bool,
     #[structopt(short, long, help = "Detect if section has appears multiple times.")]
     detect: bool,
+    #[structopt(
+        long,
+        help = "Diff in fixed-size blocks instead of loading both files fully into memory (equal-size files only)."
+    )]
+    stream: bool,
+    #[structopt(long, help = "Compress the serialized .rbp patch with zstd.")]
+    compress: bool,
+    #[structopt(
+        long,
+        default_value = "0",
+        help = "Allow up to N mismatched bytes per section window when matching (fuzzy apply)."
+    )]
+    tolerance: usize,
     #[structopt(short, long, parse(from_os_str))]
     test: Option<PathBuf>,
     #[structopt(index = 1, required = true, name = "FILE1", parse(from_os_str))]
@@ -67,22 +86,21 @@ enum Error {
         source: std::io::Error,
         path: PathBuf,
     },
-    #[snafu(display(
-        "Source and transformed file have different sizes {}!={}: {}",
-        source_size,
-        target_size,
-        source
-    ))]
-    SizeMismatch {
-        source: std::io::Error,
-        source_size: u64,
-        target_size: u64,
-    },
     #[snafu(display("Unable to read patch file {}: {}", path.display(), source))]
     ReadPatch {
         source: std::io::Error,
         path: PathBuf,
     },
+    #[snafu(display(
+        "Source file {} does not match the file this patch was built from",
+        path.display()
+    ))]
+    SourceMismatch { path: PathBuf },
+    #[snafu(display(
+        "Patched result does not match the expected output for {}",
+        path.display()
+    ))]
+    TargetMismatch { path: PathBuf },
     #[snafu(display("Error converting path to json {:?}: {}", patch, source))]
     SerializePatch {
         source: serde_json::error::Error,
@@ -116,23 +134,168 @@ fn main() -> Result<()> {
 }
 
 fn build_patch(opt: &Options) -> Result<()> {
-    let input_size = fs::metadata(&opt.input).context(ReadSource { path: &opt.input })?;
-    let patched_size = fs::metadata(&opt.patch).context(ReadTarget { path: &opt.patch })?;
-
-    if input_size.len() != patched_size.len() {
-        println!("Different file sizes.");
-        return Err(std::io::Error::new(
-            std::io::ErrorKind::UnexpectedEof,
-            "File size mismatch.",
-        ))
-        .context(SizeMismatch {
-            source_size: input_size.len(),
-            target_size: patched_size.len(),
-        });
+    if opt.stream {
+        let input_container = codec::detect_file(&opt.input).context(ReadSource { path: &opt.input })?;
+        let patched_container = codec::detect_file(&opt.patch).context(ReadTarget { path: &opt.patch })?;
+        if input_container != codec::Container::Raw || patched_container != codec::Container::Raw {
+            // build_patch_streaming reads/hashes raw file bytes without ever
+            // going through the codec layer, so a compressed input would
+            // silently diff/hash the compressed container instead of its
+            // content. Decoding it first would need the whole file in
+            // memory anyway, defeating the point of --stream, so fall back
+            // to the in-memory path, which already decodes.
+            println!("--stream does not support compressed inputs; loading both files into memory instead.");
+        } else {
+            let input_len = fs::metadata(&opt.input).context(ReadSource { path: &opt.input })?.len();
+            let patched_len = fs::metadata(&opt.patch).context(ReadTarget { path: &opt.patch })?.len();
+            if input_len == patched_len {
+                return build_patch_streaming(opt, input_len);
+            }
+            println!("--stream only supports files of equal size; loading both files into memory instead.");
+        }
+    }
+
+    let raw_input = fs::read(&opt.input).context(ReadSource { path: &opt.input })?;
+    let input = codec::decode(&raw_input).context(ReadSource { path: &opt.input })?;
+    let raw_patched = fs::read(&opt.patch).context(ReadTarget { path: &opt.patch })?;
+    let patched = codec::decode(&raw_patched).context(ReadTarget { path: &opt.patch })?;
+
+    let mut patch = Patch::default();
+
+    if input.len() == patched.len() {
+        // Fast path: files are the same length, so a simple in-place byte
+        // comparison is enough to build replace-only sections.
+        let mut patching = false;
+        let mut section_index = 0;
+        let mut fail_count = 0;
+        let mut fail_continue = false;
+        let mut extra_search: Vec<u8> = Vec::new();
+        let mut extra_data: Vec<u8> = Vec::new();
+
+        println!("Scanning files for differences...");
+
+        for i in 0..input.len() {
+            let valid = input[i] >= 0x30 && input[i] <= 0x71 || !opt.only_char;
+            if input[i] != patched[i] && valid {
+                if !patching && !fail_continue {
+                    patching = true;
+                    patch.sections.push(PatchSection::default());
+                    section_index += 1;
+                    patch.sections[section_index - 1].id = section_index as u32;
+                    patch.sections[section_index - 1].start = i;
+                    fail_count = 0;
+                }
+
+                if fail_continue {
+                    patch.sections[section_index - 1]
+                        .search
+                        .append(&mut extra_search);
+                    patch.sections[section_index - 1]
+                        .data
+                        .append(&mut extra_data);
+                    extra_search.clear();
+                    extra_data.clear();
+                    patching = true;
+                }
+
+                patch.sections[section_index - 1].search.push(input[i]);
+                patch.sections[section_index - 1].data.push(patched[i]);
+                patch.sections[section_index - 1].end = i;
+                fail_continue = false;
+            } else {
+                if fail_count < opt.follow && section_index > 0 && valid {
+                    extra_search.push(input[i]);
+                    extra_data.push(patched[i]);
+                    fail_continue = true;
+                } else {
+                    extra_search.clear();
+                    extra_data.clear();
+                    fail_continue = false;
+                }
+                fail_count += 1;
+                patching = false;
+            }
+        }
+
+        println!("Fixing small sections...");
+        if !patch.sections.is_empty() {
+            for i in 0..patch.sections.len() {
+                grow_section(&mut patch.sections[i], &input, &patched, opt)?;
+            }
+        }
+
+        println!("Merging sections...");
+        section_merge(&mut patch);
+    } else {
+        // Files differ in length: fall back to a real insert/delete diff.
+        println!(
+            "Files differ in size ({} vs {} bytes), using Myers diff...",
+            input.len(),
+            patched.len()
+        );
+        let edits = myers::diff(&input, &patched);
+        patch.sections = myers::edits_to_sections(&edits, &input, &patched);
+
+        println!("Fixing small sections...");
+        let test_file = match &opt.test {
+            Some(x) => fs::read(x).context(ReadTest { path: x })?,
+            None => input.clone(),
+        };
+        for i in 0..patch.sections.len() {
+            let limit = patch.sections.get(i + 1).map(|s| s.start);
+            grow_myers_section(&mut patch.sections[i], &input, &test_file, limit);
+        }
+    }
+
+    patch.source_hash = hash::ContentHash::of_bytes(&input);
+    patch.target_hash = hash::full_hash(&patched);
+
+    println!("Final patch has {} sections.", &patch.sections.len());
+    let mut patch_filename = match &opt.output {
+        Some(x) => x.clone(),
+        None => opt.input.clone(),
+    };
+    patch_filename.set_extension("rbp");
+
+    let coded = serialize_patch(&patch, opt.compress);
+    fs::write(&patch_filename, coded).context(WritePatch {
+        path: &patch_filename,
+    })?;
+
+    let coded = serde_json::to_string(&patch).context(SerializePatch { patch })?;
+    patch_filename.set_extension("json");
+    fs::write(&patch_filename, coded).context(WritePatch {
+        path: &patch_filename,
+    })?;
+    Ok(())
+}
+
+/// Serializes a patch to bincode, optionally compressing it with zstd so
+/// runs of near-identical `search`/`data` bytes take up less space on disk.
+fn serialize_patch(patch: &Patch, compress: bool) -> Vec<u8> {
+    let coded = bincode::serialize(patch).unwrap();
+    if compress {
+        codec::ZstdCodec.encode(&coded).unwrap()
+    } else {
+        coded
     }
+}
 
-    let input = fs::read(&opt.input).context(ReadSource { path: &opt.input })?;
-    let patched = fs::read(&opt.patch).context(ReadTarget { path: &opt.patch })?;
+/// Block size used by [`build_patch_streaming`] to walk both files without
+/// buffering them whole.
+const STREAM_BLOCK_SIZE: usize = 64 * 1024;
+
+/// Streams both files in fixed blocks instead of loading them whole, for
+/// inputs too large to fit in memory. Carries the section-building state
+/// (including the `follow` outlier buffer) across block boundaries, so the
+/// scan finds the same sections as the in-memory fast path. Section growth
+/// differs: a section still not unique after the maximum number of growth
+/// steps keeps its grown pattern here, while the in-memory path keeps the
+/// original one. Only handles the
+/// equal-size replace-only case; [`myers::diff`] still needs a full buffer.
+fn build_patch_streaming(opt: &Options, len: u64) -> Result<()> {
+    let mut input_file = fs::File::open(&opt.input).context(ReadSource { path: &opt.input })?;
+    let mut patched_file = fs::File::open(&opt.patch).context(ReadTarget { path: &opt.patch })?;
 
     let mut patch = Patch::default();
     let mut patching = false;
@@ -142,62 +305,77 @@ fn build_patch(opt: &Options) -> Result<()> {
     let mut extra_search: Vec<u8> = Vec::new();
     let mut extra_data: Vec<u8> = Vec::new();
 
-    println!("Scanning files for differences...");
+    println!("Scanning files for differences (streaming)...");
 
-    for i in 0..input.len() {
-        let valid = input[i] >= 0x30 && input[i] <= 0x71 || !opt.only_char;
-        if input[i] != patched[i] && valid {
-            if !patching && !fail_continue {
-                patching = true;
-                patch.sections.push(PatchSection::default());
-                section_index += 1;
-                patch.sections[section_index - 1].id = section_index as u32;
-                patch.sections[section_index - 1].start = i;
-                fail_count = 0;
-            }
+    let mut input_buf = vec![0u8; STREAM_BLOCK_SIZE];
+    let mut patched_buf = vec![0u8; STREAM_BLOCK_SIZE];
+    let mut remaining = len;
+    let mut offset: usize = 0;
 
-            if fail_continue {
-                patch.sections[section_index - 1]
-                    .search
-                    .append(&mut extra_search);
-                patch.sections[section_index - 1]
-                    .data
-                    .append(&mut extra_data);
-                extra_search.clear();
-                extra_data.clear();
-                patching = true;
-            }
+    while remaining > 0 {
+        let want = remaining.min(STREAM_BLOCK_SIZE as u64) as usize;
+        read_block(&mut input_file, &mut input_buf[..want]).context(ReadSource { path: &opt.input })?;
+        read_block(&mut patched_file, &mut patched_buf[..want]).context(ReadTarget { path: &opt.patch })?;
 
-            patch.sections[section_index - 1].search.push(input[i]);
-            patch.sections[section_index - 1].data.push(patched[i]);
-            patch.sections[section_index - 1].end = i;
-            fail_continue = false;
-        } else {
-            if fail_count < opt.follow && section_index > 0 && valid {
-                extra_search.push(input[i]);
-                extra_data.push(patched[i]);
-                fail_continue = true;
-            } else {
-                extra_search.clear();
-                extra_data.clear();
+        for j in 0..want {
+            let i = offset + j;
+            let valid = input_buf[j] >= 0x30 && input_buf[j] <= 0x71 || !opt.only_char;
+            if input_buf[j] != patched_buf[j] && valid {
+                if !patching && !fail_continue {
+                    patching = true;
+                    patch.sections.push(PatchSection::default());
+                    section_index += 1;
+                    patch.sections[section_index - 1].id = section_index as u32;
+                    patch.sections[section_index - 1].start = i;
+                    fail_count = 0;
+                }
+
+                if fail_continue {
+                    patch.sections[section_index - 1]
+                        .search
+                        .append(&mut extra_search);
+                    patch.sections[section_index - 1]
+                        .data
+                        .append(&mut extra_data);
+                    extra_search.clear();
+                    extra_data.clear();
+                    patching = true;
+                }
+
+                patch.sections[section_index - 1].search.push(input_buf[j]);
+                patch.sections[section_index - 1].data.push(patched_buf[j]);
+                patch.sections[section_index - 1].end = i;
                 fail_continue = false;
+            } else {
+                if fail_count < opt.follow && section_index > 0 && valid {
+                    extra_search.push(input_buf[j]);
+                    extra_data.push(patched_buf[j]);
+                    fail_continue = true;
+                } else {
+                    extra_search.clear();
+                    extra_data.clear();
+                    fail_continue = false;
+                }
+                fail_count += 1;
+                patching = false;
             }
-            fail_count += 1;
-            patching = false;
         }
+
+        offset += want;
+        remaining -= want as u64;
     }
 
     println!("Fixing small sections...");
-    if !patch.sections.is_empty() {
-        for i in 0..patch.sections.len() {
-            let mut section = &mut patch.sections[i];
-            grow_section(&mut section, &input, &patched, opt)?;
-        }
+    for section in &mut patch.sections {
+        grow_section_streaming(section, &opt.input, &opt.patch, opt)?;
     }
 
     println!("Merging sections...");
     section_merge(&mut patch);
 
+    patch.source_hash = hash::content_hash_of_file(&opt.input).context(ReadSource { path: &opt.input })?;
+    patch.target_hash = hash::content_hash_of_file(&opt.patch).context(ReadTarget { path: &opt.patch })?.full;
+
     println!("Final patch has {} sections.", &patch.sections.len());
     let mut patch_filename = match &opt.output {
         Some(x) => x.clone(),
@@ -205,7 +383,7 @@ fn build_patch(opt: &Options) -> Result<()> {
     };
     patch_filename.set_extension("rbp");
 
-    let coded = bincode::serialize(&patch).unwrap();
+    let coded = serialize_patch(&patch, opt.compress);
     fs::write(&patch_filename, coded).context(WritePatch {
         path: &patch_filename,
     })?;
@@ -218,6 +396,17 @@ fn build_patch(opt: &Options) -> Result<()> {
     Ok(())
 }
 
+/// Reads exactly `buf.len()` bytes, treating an end-of-file mid-read (e.g.
+/// the file shrinking concurrently) as a short final block rather than an
+/// error, since the caller already sized `buf` from the file's length.
+fn read_block(file: &mut fs::File, buf: &mut [u8]) -> std::io::Result<()> {
+    match file.read_exact(buf) {
+        Ok(()) => Ok(()),
+        Err(ref e) if e.kind() == std::io::ErrorKind::UnexpectedEof => Ok(()),
+        Err(e) => Err(e),
+    }
+}
+
 /// Grow sections if they appear many times on the base file.
 fn grow_section(
     section: &mut PatchSection,
@@ -234,31 +423,7 @@ fn grow_section(
         None => input.to_vec(),
     };
     while after < max_grow && !section_done {
-        let mut i = 0;
-        let mut section_count = 0;
-        while i < test_file.len() {
-            if section_count > 1 {
-                break;
-            }
-
-            if test_file[i] == new_section.search[0] {
-                let mut valid_section = true;
-                // Validate section
-                for j in 0..new_section.search.len() {
-                    if i + j >= test_file.len() || test_file[i + j] != new_section.search[j] {
-                        valid_section = false;
-                        break;
-                    }
-                }
-                if valid_section {
-                    section_count += 1;
-                    i += new_section.search.len();
-                    continue;
-                }
-            }
-            i += 1;
-        }
-        if section_count > 1 {
+        if has_multiple_occurrences(&test_file, &new_section.search) {
             // println!("Detected more than one Section {:02}. Adding one extra byte.", new_section.id);
             after += 1;
             section_append(&mut new_section, input, patched, 1);
@@ -292,6 +457,128 @@ fn grow_section(
     Ok(())
 }
 
+/// Streaming counterpart of `grow_section`, used by `build_patch_streaming`:
+/// checks uniqueness a block at a time via `has_multiple_occurrences_in_file`
+/// instead of loading the whole test file, and seeks for just the one byte
+/// each growth step needs instead of holding `input`/`patched` in memory.
+fn grow_section_streaming(
+    section: &mut PatchSection,
+    input_path: &Path,
+    patched_path: &Path,
+    opt: &Options,
+) -> Result<()> {
+    let test_path: &Path = opt.test.as_deref().unwrap_or(input_path);
+    let mut input_file = fs::File::open(input_path).context(ReadSource { path: input_path })?;
+    let mut patched_file = fs::File::open(patched_path).context(ReadTarget { path: patched_path })?;
+    let max_grow = 10;
+    let mut grown = 0;
+    while grown < max_grow
+        && has_multiple_occurrences_in_file(test_path, &section.search).context(ReadTest { path: test_path })?
+    {
+        let next = section.end as u64 + 1;
+        let mut next_input = [0u8; 1];
+        let mut next_patched = [0u8; 1];
+        input_file.seek(SeekFrom::Start(next)).context(ReadSource { path: input_path })?;
+        if input_file.read_exact(&mut next_input).is_err() {
+            break;
+        }
+        patched_file.seek(SeekFrom::Start(next)).context(ReadTarget { path: patched_path })?;
+        if patched_file.read_exact(&mut next_patched).is_err() {
+            break;
+        }
+        section.search.push(next_input[0]);
+        section.data.push(next_patched[0]);
+        section.end += 1;
+        grown += 1;
+    }
+    if grown > 0 {
+        println!("Fixed Section {:02}", section.id);
+    }
+    Ok(())
+}
+
+const RK_BASE: u64 = 257;
+const RK_MOD: u64 = 1_000_000_007;
+
+/// Rabin-Karp hash of `bytes`, plus `RK_BASE^(len-1) mod RK_MOD` for rolling it.
+fn rolling_hash(bytes: &[u8]) -> (u64, u64) {
+    let mut hash = 0u64;
+    let mut high_pow = 1u64;
+    for (i, &b) in bytes.iter().enumerate() {
+        hash = (hash * RK_BASE + u64::from(b)) % RK_MOD;
+        if i > 0 {
+            high_pow = (high_pow * RK_BASE) % RK_MOD;
+        }
+    }
+    (hash, high_pow)
+}
+
+/// Checks whether `pattern` occurs more than once in `haystack` via a rolling hash.
+fn has_multiple_occurrences(haystack: &[u8], pattern: &[u8]) -> bool {
+    let len = pattern.len();
+    if len == 0 || haystack.len() < len {
+        return false;
+    }
+
+    let (pattern_hash, high_pow) = rolling_hash(pattern);
+    let mut window_hash = rolling_hash(&haystack[..len]).0;
+    let mut occurrences = 0;
+    let mut i = 0;
+    loop {
+        if window_hash == pattern_hash && &haystack[i..i + len] == pattern {
+            occurrences += 1;
+            if occurrences > 1 {
+                return true;
+            }
+        }
+        let next = i + len;
+        if next >= haystack.len() {
+            break;
+        }
+        window_hash = (window_hash + RK_MOD - (u64::from(haystack[i]) * high_pow) % RK_MOD) % RK_MOD;
+        window_hash = (window_hash * RK_BASE + u64::from(haystack[next])) % RK_MOD;
+        i += 1;
+    }
+    false
+}
+
+/// Streaming counterpart of `has_multiple_occurrences`: walks `path` a block
+/// at a time instead of requiring the whole file in memory, carrying just
+/// the last `pattern.len() - 1` bytes across block boundaries so a match
+/// straddling two blocks still isn't missed.
+fn has_multiple_occurrences_in_file(path: &Path, pattern: &[u8]) -> std::io::Result<bool> {
+    let len = pattern.len();
+    if len == 0 {
+        return Ok(false);
+    }
+    let mut file = fs::File::open(path)?;
+    let overlap = len - 1;
+    let mut buf = vec![0u8; STREAM_BLOCK_SIZE + overlap];
+    let mut carry_len = 0usize;
+    let mut occurrences = 0;
+    loop {
+        let read = file.read(&mut buf[carry_len..])?;
+        if read == 0 {
+            break;
+        }
+        let available = carry_len + read;
+        if available >= len {
+            for start in 0..=(available - len) {
+                if &buf[start..start + len] == pattern {
+                    occurrences += 1;
+                    if occurrences > 1 {
+                        return Ok(true);
+                    }
+                }
+            }
+        }
+        let keep = overlap.min(available);
+        buf.copy_within(available - keep..available, 0);
+        carry_len = keep;
+    }
+    Ok(false)
+}
+
 /// Append an extra byte from the source files
 fn section_append(section: &mut PatchSection, input: &[u8], patched: &[u8], amount: usize) {
     let mut after_search = input
@@ -307,6 +594,35 @@ fn section_append(section: &mut PatchSection, input: &[u8], patched: &[u8], amou
     section.end += amount;
 }
 
+/// Grows a Myers-diff-produced section's `search` window until it's unique
+/// in `test_file`, the same uniqueness check [`grow_section`] runs for the
+/// equal-size fast path. An `Insert` section in particular starts out
+/// anchored on a single neighbouring byte (see [`myers::edits_to_sections`]),
+/// which is virtually guaranteed to recur elsewhere in a real binary and
+/// would otherwise let an earlier, unrelated occurrence of that byte steal
+/// the match. Growth always extends forward from `section.end` into bytes
+/// that `b`'s corresponding Equal run guarantees are unchanged, so appending
+/// the same byte to both `search` and `data` keeps the edit's effect intact.
+/// `limit`, when given, is the start of the next section: growth stops
+/// before it so two sections never end up overlapping the same source byte.
+fn grow_myers_section(section: &mut PatchSection, a: &[u8], test_file: &[u8], limit: Option<usize>) {
+    let max_grow = 10;
+    let mut grown = 0;
+    while grown < max_grow
+        && !section.search.is_empty()
+        && has_multiple_occurrences(test_file, &section.search)
+    {
+        let next = section.end + 1;
+        if next >= a.len() || limit == Some(next) {
+            break;
+        }
+        section.search.push(a[next]);
+        section.data.push(a[next]);
+        section.end = next;
+        grown += 1;
+    }
+}
+
 /// Merge sections that overlap with a lazy strategy
 fn section_merge(patch: &mut Patch) -> bool {
     if patch.sections.len() == 1 {
@@ -346,101 +662,582 @@ fn section_merge(patch: &mut Patch) -> bool {
     true
 }
 
-/// Applies a patch file
-fn apply_patch(opt: &Options) -> Result<()> {
-    let input = fs::read(&opt.input).context(ReadSource { path: &opt.input })?;
-    let path = std::path::Path::new(&opt.patch);
-    let patched = fs::read(&opt.patch).context(ReadPatch { path: &opt.patch })?;
+/// A single occurrence of a section's `search` pattern: the matched byte
+/// range and, for a fuzzy match, the absolute positions that differed.
+type SectionMatch = (usize, usize, Vec<usize>);
 
-    // Loads our patch information (can be bincode or json)
-    let patch: Patch = if path.extension().unwrap_or_default() == "json" {
-        serde_json::from_str(&String::from_utf8(patched).unwrap()).unwrap()
+/// Finds every occurrence of every section's search pattern in `input` via
+/// Aho-Corasick, overlapping so one pattern prefixing another isn't swallowed.
+fn find_section_matches(patch: &Patch, input: &[u8]) -> Vec<Vec<SectionMatch>> {
+    let mut matches = vec![Vec::new(); patch.sections.len()];
+    if patch.sections.is_empty() {
+        return matches;
+    }
+    let patterns: Vec<&[u8]> = patch.sections.iter().map(|s| s.search.as_slice()).collect();
+    let ac = AhoCorasick::new(&patterns);
+    for m in ac.find_overlapping_iter(input) {
+        matches[m.pattern()].push((m.start(), m.end(), Vec::new()));
+    }
+    matches
+}
+
+/// Finds every window of `input` that matches `pattern` with at most
+/// `tolerance` mismatched bytes, verifying each candidate with a full
+/// byte-by-byte compare and recording where the mismatches were. A tolerance
+/// anywhere close to `pattern.len()` lets windows with almost no resemblance
+/// to `pattern` qualify, so it's clamped to require more than half the
+/// pattern to actually match, not merely "not every byte".
+fn find_fuzzy_matches(input: &[u8], pattern: &[u8], tolerance: usize) -> Vec<SectionMatch> {
+    let mut matches = Vec::new();
+    if pattern.is_empty() || input.len() < pattern.len() {
+        return matches;
+    }
+    let max_tolerance = pattern.len().saturating_sub(1) / 2;
+    let tolerance = if tolerance > max_tolerance {
+        println!(
+            "Tolerance {} would accept a window of {} byte(s) that mostly doesn't match; clamping to {} so more than half the pattern still has to match.",
+            tolerance, pattern.len(), max_tolerance
+        );
+        max_tolerance
     } else {
-        bincode::deserialize(&patched).unwrap()
+        tolerance
     };
-    println!("Sections found: {}", &patch.sections.len());
-    let mut section_index = 0;
-    let mut i;
-    let mut result: Vec<u8> = Vec::new();
-    let mut section_count = 0;
-    if opt.detect {
-        for (k, section) in patch.sections.iter().enumerate() {
-            i = 0;
-            while i < input.len() {
-                if input[i] == section.search[0] {
-                    let mut valid_section = true;
-                    // Validate section
-                    for j in 0..section.search.len() {
-                        if input[i + j] != section.search[j] {
-                            valid_section = false;
-                            break;
-                        }
-                    }
-                    if valid_section {
-                        section_count += 1;
-                        println!("Detected section {:2} at offset {}", k + 1, i);
-                        i += section.search.len();
-                        continue;
-                    }
+    for start in 0..=(input.len() - pattern.len()) {
+        let mut mismatches = Vec::new();
+        for (j, &expected) in pattern.iter().enumerate() {
+            if input[start + j] != expected {
+                mismatches.push(start + j);
+                if mismatches.len() > tolerance {
+                    break;
                 }
-                i += 1;
             }
         }
+        if mismatches.len() <= tolerance {
+            matches.push((start, start + pattern.len(), mismatches));
+        }
     }
-    if section_count > patch.sections.len() {
-        panic!("Too many sections found.");
-    }
-
-    i = 0;
-    // Search the input file for the patch sections
-    while i < input.len() && section_index < patch.sections.len() {
-        let section = &patch.sections[section_index];
+    matches
+}
 
-        if input[i] == section.search[0] {
-            let mut valid_section = true;
-            // Validate section
-            for j in 0..section.search.len() {
-                if input[i + j] != section.search[j] {
-                    valid_section = false;
-                    break;
-                }
-            }
+/// Dispatches to an exact or fuzzy section search depending on `tolerance`.
+fn find_section_matches_with_tolerance(
+    patch: &Patch,
+    input: &[u8],
+    tolerance: usize,
+) -> Vec<Vec<SectionMatch>> {
+    if tolerance == 0 {
+        return find_section_matches(patch, input);
+    }
+    patch
+        .sections
+        .iter()
+        .map(|section| find_fuzzy_matches(input, &section.search, tolerance))
+        .collect()
+}
 
-            // Apply the section
-            if valid_section {
+/// Walks `patch`'s sections in order, each one claiming the best-matching
+/// occurrence in `matches_by_section` that starts at or after where the
+/// previous section left off, and splices its `data` in over the matched
+/// window. "Best" means fewest mismatched bytes; if more than one candidate
+/// ties for fewest, the section is treated as unmatched rather than guessing
+/// which one is right. Returns the patched bytes together with how many
+/// sections actually matched, so the caller can tell a clean apply from one
+/// that ran out of matches partway through.
+fn apply_sections(
+    patch: &Patch,
+    input: &[u8],
+    matches_by_section: &[Vec<SectionMatch>],
+) -> (Vec<u8>, usize) {
+    let mut result: Vec<u8> = Vec::new();
+    let mut cursor = 0;
+    let mut section_index = 0;
+    for (idx, section) in patch.sections.iter().enumerate() {
+        let candidates: Vec<&SectionMatch> = matches_by_section[idx]
+            .iter()
+            .filter(|(start, _, _)| *start >= cursor)
+            .collect();
+        let best = candidates.iter().map(|(_, _, m)| m.len()).min();
+        let hit = best.and_then(|best| {
+            let mut winners = candidates.iter().filter(|(_, _, m)| m.len() == best);
+            let first = winners.next();
+            if winners.next().is_some() {
                 println!(
-                    "Applied section {:02} at index {} with len {}",
-                    section_index + 1,
-                    i,
-                    section.data.len()
+                    "Section {:02}: more than one equally good match at or after index {}; refusing to guess.",
+                    idx + 1,
+                    cursor
                 );
-                result.append(&mut section.data.clone());
+                None
+            } else {
+                first.copied()
+            }
+        });
+        match hit {
+            Some((start, end, mismatches)) => {
+                result.extend_from_slice(&input[cursor..*start]);
+                if mismatches.is_empty() {
+                    println!(
+                        "Applied section {:02} at index {} with len {}",
+                        idx + 1,
+                        start,
+                        section.data.len()
+                    );
+                } else {
+                    println!(
+                        "Applied section {:02} at index {} with len {} ({} mismatched byte(s) at {:?})",
+                        idx + 1,
+                        start,
+                        section.data.len(),
+                        mismatches.len(),
+                        mismatches
+                    );
+                }
+                result.extend_from_slice(&section.data);
+                cursor = *end;
                 section_index += 1;
-                i += section.search.len();
-                continue;
             }
+            None => break,
         }
-        result.push(input[i]);
-        i += 1;
     }
 
     // Add any missing file bytes.
-    if i < input.len() {
-        let mut section = input[i..input.len()].to_vec().clone();
-        result.append(&mut section);
+    if cursor < input.len() {
+        result.extend_from_slice(&input[cursor..]);
     }
 
+    (result, section_index)
+}
+
+/// Applies a patch file
+fn apply_patch(opt: &Options) -> Result<()> {
+    let path = std::path::Path::new(&opt.patch);
+    let raw_patch_bytes = fs::read(&opt.patch).context(ReadPatch { path: &opt.patch })?;
+
+    // Loads our patch information (can be bincode, optionally zstd-compressed,
+    // or json)
+    let patch: Patch = if path.extension().unwrap_or_default() == "json" {
+        serde_json::from_str(&String::from_utf8(raw_patch_bytes).unwrap()).unwrap()
+    } else {
+        let decoded = codec::decode(&raw_patch_bytes).context(ReadPatch { path: &opt.patch })?;
+        bincode::deserialize(&decoded).unwrap()
+    };
+    println!("Sections found: {}", &patch.sections.len());
+
+    // Sniff the input's container without reading it in full: for an
+    // uncompressed input, the partial/full hash pre-check can still avoid
+    // buffering the whole file. A compressed container needs the whole file
+    // to decompress, so its integrity is confirmed against the decoded bytes
+    // instead.
+    let input_container = codec::detect_file(&opt.input).context(ReadSource { path: &opt.input })?;
+    let input = if opt.tolerance > 0 {
+        // A tolerant apply is meant for a file that has legitimately drifted
+        // from the one the patch was built against, so the whole-file
+        // integrity check (which would always reject that drift) is skipped;
+        // the tolerance itself only relaxes the per-section window
+        // comparison below.
+        println!(
+            "Tolerance set to {}: skipping whole-file integrity check.",
+            opt.tolerance
+        );
+        let raw = fs::read(&opt.input).context(ReadSource { path: &opt.input })?;
+        codec::for_container(input_container)
+            .decode(&raw)
+            .context(ReadSource { path: &opt.input })?
+    } else if patch.source_hash.is_unknown() {
+        // A patch built before source/target hashing was added has no
+        // recorded hash to check against; treat it as unverified rather than
+        // rejecting every pre-existing patch as a mismatch.
+        println!("Patch has no recorded source hash: skipping integrity check.");
+        let raw = fs::read(&opt.input).context(ReadSource { path: &opt.input })?;
+        codec::for_container(input_container)
+            .decode(&raw)
+            .context(ReadSource { path: &opt.input })?
+    } else if input_container == codec::Container::Raw {
+        let actual_partial =
+            hash::partial_hash_of_file(&opt.input).context(ReadSource { path: &opt.input })?;
+        if actual_partial != patch.source_hash.partial {
+            return SourceMismatch { path: opt.input.clone() }.fail();
+        }
+        let actual_full =
+            hash::full_hash_of_file(&opt.input).context(ReadSource { path: &opt.input })?;
+        if actual_full != patch.source_hash.full {
+            return SourceMismatch { path: opt.input.clone() }.fail();
+        }
+        fs::read(&opt.input).context(ReadSource { path: &opt.input })?
+    } else {
+        let raw = fs::read(&opt.input).context(ReadSource { path: &opt.input })?;
+        let decoded = codec::for_container(input_container)
+            .decode(&raw)
+            .context(ReadSource { path: &opt.input })?;
+        if hash::ContentHash::of_bytes(&decoded) != patch.source_hash {
+            return SourceMismatch { path: opt.input.clone() }.fail();
+        }
+        decoded
+    };
+
+    let matches_by_section = find_section_matches_with_tolerance(&patch, &input, opt.tolerance);
+
+    if opt.detect {
+        let mut section_count = 0;
+        for (k, occurrences) in matches_by_section.iter().enumerate() {
+            for (start, _, mismatches) in occurrences {
+                section_count += 1;
+                if mismatches.is_empty() {
+                    println!("Detected section {:2} at offset {}", k + 1, start);
+                } else {
+                    println!(
+                        "Detected section {:2} at offset {} with {} mismatched byte(s)",
+                        k + 1,
+                        start,
+                        mismatches.len()
+                    );
+                }
+            }
+        }
+        if section_count > patch.sections.len() {
+            println!(
+                "Warning: found {} occurrence(s) across {} section(s); expected at most one per section. The input may differ significantly from what this patch targets, or --tolerance may be too loose.",
+                section_count,
+                patch.sections.len()
+            );
+        }
+    }
+
+    let (result, section_index) = apply_sections(&patch, &input, &matches_by_section);
+
     // Check if we parsed all sections
     if section_index != patch.sections.len() {
         println!("Failed to apply patch.");
     } else {
+        if opt.tolerance == 0 && patch.target_hash != 0 && hash::full_hash(&result) != patch.target_hash {
+            return TargetMismatch { path: opt.input.clone() }.fail();
+        }
+        // Re-wrap the result in the input's original container when we can;
+        // fall back to writing it raw if that format can't be re-encoded.
+        let output = match codec::for_container(input_container).encode(&result) {
+            Ok(encoded) => encoded,
+            Err(e) => {
+                println!(
+                    "Could not re-encode the result back into its original container ({}); writing raw/decompressed bytes instead.",
+                    e
+                );
+                result
+            }
+        };
         // And save the patched file.
         println!("Patch applied.");
         let mut patch_filename = opt.input.clone();
         patch_filename.set_extension("patched");
-        fs::write(&patch_filename, &result).context(WritePatchedFile {
+        fs::write(&patch_filename, &output).context(WritePatchedFile {
             path: &patch_filename,
         })?;
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn section(id: u32, search: &[u8], data: &[u8]) -> PatchSection {
+        PatchSection {
+            id,
+            start: 0,
+            end: 0,
+            search: search.to_vec(),
+            data: data.to_vec(),
+            op: patch::SectionOp::Replace,
+        }
+    }
+
+    #[test]
+    fn overlapping_and_prefixing_patterns_are_all_found() {
+        // Regression test for the shared, non-overlapping Aho-Corasick scan
+        // silently dropping a section whose pattern overlaps or prefixes
+        // another section's: "ab" at 1..3 used to consume the match, leaving
+        // "abc" (a longer pattern at the same offset) and "bc" (overlapping)
+        // with no recorded occurrence at all.
+        let mut patch = Patch::default();
+        patch.sections.push(section(1, b"ab", b"AB"));
+        patch.sections.push(section(2, b"abc", b"ABC"));
+        patch.sections.push(section(3, b"bc", b"BC"));
+
+        let matches = find_section_matches(&patch, b"xabcx");
+        assert_eq!(matches[0], vec![(1, 3, Vec::new())]);
+        assert_eq!(matches[1], vec![(1, 4, Vec::new())]);
+        assert_eq!(matches[2], vec![(2, 4, Vec::new())]);
+    }
+
+    #[test]
+    fn myers_insert_round_trips_when_anchor_byte_recurs() {
+        // Regression test for an Insert section's single-byte anchor
+        // recurring earlier in the source than its true location: force the
+        // three bytes around the insertion point's anchor to also appear a
+        // few bytes into the file, so an un-grown anchor would match that
+        // earlier, wrong occurrence.
+        let mut a: Vec<u8> = (0..2000usize).map(pseudo_byte).collect();
+        let dup = [a[499], a[500], a[501]];
+        a[50..53].copy_from_slice(&dup);
+        let mut b = a[..500].to_vec();
+        b.extend_from_slice(&[0xAA, 0xBB, 0xCC, 0xDD, 0xEE]);
+        b.extend_from_slice(&a[500..]);
+
+        let edits = myers::diff(&a, &b);
+        let mut sections = myers::edits_to_sections(&edits, &a, &b);
+        for i in 0..sections.len() {
+            let limit = sections.get(i + 1).map(|s| s.start);
+            grow_myers_section(&mut sections[i], &a, &a, limit);
+        }
+
+        let patch = Patch {
+            sections,
+            ..Patch::default()
+        };
+
+        let matches = find_section_matches_with_tolerance(&patch, &a, 0);
+        let (result, section_index) = apply_sections(&patch, &a, &matches);
+        assert_eq!(section_index, patch.sections.len());
+        assert_eq!(result, b);
+    }
+
+    #[test]
+    fn myers_sections_round_trip_when_insert_anchor_touches_prior_section() {
+        // Regression test for a lone Insert anchored on the byte that's also
+        // the last byte of the immediately preceding section (no equal run
+        // between them): that byte belongs to the previous edit, not an
+        // unchanged run, so anchoring a second section on it would overlap
+        // the first and make apply_sections drop one of them.
+        let a: Vec<u8> = (0..40usize).map(pseudo_byte).collect();
+        let mut b = a[..10].to_vec();
+        b.extend_from_slice(&[0xAA, 0xBB]);
+        b.extend_from_slice(&[0xCC, 0xDD, 0xEE]);
+        b.extend_from_slice(&a[12..]);
+
+        let edits = vec![
+            myers::Edit::Equal { a: 0, b: 0, len: 10 },
+            myers::Edit::Delete { a: 10, len: 2 },
+            myers::Edit::Insert { a: 12, b: 10, len: 2 },
+            myers::Edit::Insert { a: 12, b: 12, len: 3 },
+            myers::Edit::Equal { a: 12, b: 15, len: a.len() - 12 },
+        ];
+        let mut sections = myers::edits_to_sections(&edits, &a, &b);
+        assert_eq!(sections.len(), 1, "insert should fold into the replace section");
+        for i in 0..sections.len() {
+            let limit = sections.get(i + 1).map(|s| s.start);
+            grow_myers_section(&mut sections[i], &a, &a, limit);
+        }
+
+        let patch = Patch {
+            sections,
+            ..Patch::default()
+        };
+
+        let matches = find_section_matches_with_tolerance(&patch, &a, 0);
+        let (result, section_index) = apply_sections(&patch, &a, &matches);
+        assert_eq!(section_index, patch.sections.len());
+        assert_eq!(result, b);
+    }
+
+    /// Deterministic pseudo-random byte, used in tests to build buffers that
+    /// don't have the short-range periodicity a simple `i % N` or linear
+    /// congruential generator has: this is SplitMix64's finalizer, which
+    /// avalanches `i` well enough that a handful of consecutive bytes is
+    /// already unique across a few KB of output.
+    fn pseudo_byte(i: usize) -> u8 {
+        let mut x = (i as u64).wrapping_add(0x9E37_79B9_7F4A_7C15);
+        x = (x ^ (x >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        x = (x ^ (x >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        x ^= x >> 31;
+        x as u8
+    }
+
+    #[test]
+    fn streaming_build_grows_non_unique_sections() {
+        // Regression test for build_patch_streaming keeping a section's
+        // 1-byte search pattern even when it isn't unique in the source:
+        // force a short duplicate window around one diff's anchor byte, and
+        // check the streaming build grows past it, round-tripping correctly
+        // the same way the in-memory build already does.
+        let len = 4096usize;
+        let mut base: Vec<u8> = (0..len).map(pseudo_byte).collect();
+        let (head, tail) = base.split_at_mut(3000);
+        tail[0..3].copy_from_slice(&head[100..103]);
+
+        let mut patched = base.clone();
+        patched[100] = base[100].wrapping_add(1);
+        patched[3500] = base[3500].wrapping_add(1);
+
+        let dir = std::env::temp_dir().join(format!(
+            "rs_bin_patcher_test_{}_{}",
+            std::process::id(),
+            "streaming_build_grows_non_unique_sections"
+        ));
+        std::fs::create_dir_all(&dir).unwrap();
+        let input_path = dir.join("input.bin");
+        let patched_path = dir.join("patched.bin");
+        std::fs::write(&input_path, &base).unwrap();
+        std::fs::write(&patched_path, &patched).unwrap();
+
+        let opt = Options {
+            mode: Mode::Diff,
+            follow: 6,
+            only_char: false,
+            detect: false,
+            stream: true,
+            compress: false,
+            tolerance: 0,
+            test: None,
+            input: input_path.clone(),
+            patch: patched_path.clone(),
+            output: None,
+        };
+
+        build_patch_streaming(&opt, len as u64).unwrap();
+
+        let mut rbp_path = input_path.clone();
+        rbp_path.set_extension("rbp");
+        let raw = fs::read(&rbp_path).unwrap();
+        let decoded = codec::decode(&raw).unwrap();
+        let patch: Patch = bincode::deserialize(&decoded).unwrap();
+
+        assert!(
+            patch.sections.iter().any(|s| s.search.len() > 1),
+            "streaming build should have grown at least one non-unique 1-byte section"
+        );
+
+        let matches = find_section_matches_with_tolerance(&patch, &base, 0);
+        let (result, section_index) = apply_sections(&patch, &base, &matches);
+        assert_eq!(section_index, patch.sections.len());
+        assert_eq!(result, patched);
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn stream_falls_back_for_compressed_input() {
+        // Regression test for --stream hashing/diffing a compressed
+        // container's raw bytes instead of its decoded content: a
+        // zstd-compressed input should fall back to the in-memory path,
+        // whose source_hash is taken over the decoded bytes.
+        let content_a: Vec<u8> = (0..500u32).map(|i| i as u8).collect();
+        let mut content_b = content_a.clone();
+        content_b[200] = 0xFF;
+
+        let raw_a = codec::ZstdCodec.encode(&content_a).unwrap();
+        let raw_b = codec::ZstdCodec.encode(&content_b).unwrap();
+
+        let dir = std::env::temp_dir().join(format!(
+            "rs_bin_patcher_test_{}_{}",
+            std::process::id(),
+            "stream_falls_back_for_compressed_input"
+        ));
+        std::fs::create_dir_all(&dir).unwrap();
+        let input_path = dir.join("input.bin");
+        let patched_path = dir.join("patched.bin");
+        std::fs::write(&input_path, &raw_a).unwrap();
+        std::fs::write(&patched_path, &raw_b).unwrap();
+
+        let opt = Options {
+            mode: Mode::Diff,
+            follow: 6,
+            only_char: false,
+            detect: false,
+            stream: true,
+            compress: false,
+            tolerance: 0,
+            test: None,
+            input: input_path.clone(),
+            patch: patched_path.clone(),
+            output: None,
+        };
+
+        build_patch(&opt).unwrap();
+
+        let mut rbp_path = input_path.clone();
+        rbp_path.set_extension("rbp");
+        let raw = fs::read(&rbp_path).unwrap();
+        let decoded = codec::decode(&raw).unwrap();
+        let patch: Patch = bincode::deserialize(&decoded).unwrap();
+
+        assert_eq!(patch.source_hash, hash::ContentHash::of_bytes(&content_a));
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn fuzzy_tolerance_is_clamped_below_pattern_len() {
+        // Regression test for a 1-byte search window (what Myers insert
+        // anchors produce) under any tolerance >= 1 matching every byte in
+        // the haystack, since `mismatches.len() <= tolerance` became
+        // trivially true. The clamp should force at least one byte to still
+        // match, so only the true occurrence is returned.
+        let haystack = b"xxxAxxx";
+        let matches = find_fuzzy_matches(haystack, b"A", 5);
+        assert_eq!(matches, vec![(3, 4, Vec::new())]);
+    }
+
+    #[test]
+    fn fuzzy_tolerance_is_clamped_to_a_minority_of_the_pattern() {
+        // Regression test: a tolerance merely close to (not >=) pattern.len()
+        // used to pass the old clamp unchanged and accept windows that
+        // barely resemble the pattern at all. A 10-byte pattern with
+        // tolerance 9 should now clamp down to 4 (fewer than half the bytes
+        // may mismatch), so an unrelated window full of mismatches is
+        // rejected.
+        let pattern = b"ABCDEFGHIJ";
+        let unrelated = b"0123456789";
+        assert!(find_fuzzy_matches(unrelated, pattern, 9).is_empty());
+        assert_eq!(find_fuzzy_matches(pattern, pattern, 9), vec![(0, 10, Vec::new())]);
+    }
+
+    #[test]
+    fn apply_sections_prefers_the_fewest_mismatches_not_the_first_match() {
+        // Regression test for the "silent corruption" bug: with a loose
+        // tolerance, find_fuzzy_matches can return several candidates for a
+        // section, and the true, exact occurrence might not be the first one
+        // at or after the cursor. apply_sections must pick the best (fewest
+        // mismatches) candidate, not just the first.
+        let section = PatchSection {
+            id: 1,
+            start: 0,
+            end: 9,
+            search: b"ABCDEFGHIJ".to_vec(),
+            data: b"matched!!!".to_vec(),
+            op: patch::SectionOp::Replace,
+        };
+        let patch = Patch {
+            sections: vec![section],
+            ..Patch::default()
+        };
+        // A decoy with one mismatched byte earlier in the file, and the true
+        // exact match later on.
+        let matches_by_section = vec![vec![(5, 15, vec![12]), (50, 60, Vec::new())]];
+        let mut input = vec![0u8; 70];
+        input[50..60].copy_from_slice(b"ABCDEFGHIJ");
+        let (result, section_index) = apply_sections(&patch, &input, &matches_by_section);
+        assert_eq!(section_index, 1);
+        assert_eq!(&result[50..60], b"matched!!!");
+    }
+
+    #[test]
+    fn apply_sections_refuses_a_tied_match() {
+        // Two equally-good candidates for the same section: picking either
+        // one would be a guess, so the section should be left unmatched
+        // rather than silently choosing one.
+        let section = PatchSection {
+            id: 1,
+            start: 0,
+            end: 2,
+            search: b"ABC".to_vec(),
+            data: b"xyz".to_vec(),
+            op: patch::SectionOp::Replace,
+        };
+        let patch = Patch {
+            sections: vec![section],
+            ..Patch::default()
+        };
+        let matches_by_section = vec![vec![(0, 3, vec![2]), (10, 13, vec![2])]];
+        let input = vec![0u8; 20];
+        let (_, section_index) = apply_sections(&patch, &input, &matches_by_section);
+        assert_eq!(section_index, 0);
+    }
+}
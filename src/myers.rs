@@ -0,0 +1,211 @@
+//! Myers' O(ND) shortest-edit-script diff, used to build patch sections for
+//! source/target files that don't share the same length.
+
+use crate::patch::{PatchSection, SectionOp};
+
+#[derive(Clone, Copy, Debug)]
+pub(crate) enum Edit {
+    Equal { a: usize, b: usize, len: usize },
+    Delete { a: usize, len: usize },
+    Insert { a: usize, b: usize, len: usize },
+}
+
+/// Computes the shortest edit script turning `a` into `b`.
+pub(crate) fn diff(a: &[u8], b: &[u8]) -> Vec<Edit> {
+    let n = a.len() as i64;
+    let m = b.len() as i64;
+    let max = n + m;
+    if max == 0 {
+        return Vec::new();
+    }
+
+    let offset = max as usize;
+    let size = 2 * max as usize + 1;
+    let mut v = vec![0i64; size];
+    let mut trace: Vec<Vec<i64>> = Vec::new();
+
+    'search: for d in 0..=max {
+        trace.push(v.clone());
+        let mut k = -d;
+        while k <= d {
+            let idx = |k: i64| (k + offset as i64) as usize;
+            let mut x = if k == -d || (k != d && v[idx(k - 1)] < v[idx(k + 1)]) {
+                v[idx(k + 1)]
+            } else {
+                v[idx(k - 1)] + 1
+            };
+            let mut y = x - k;
+            while x < n && y < m && a[x as usize] == b[y as usize] {
+                x += 1;
+                y += 1;
+            }
+            v[idx(k)] = x;
+            if x >= n && y >= m {
+                break 'search;
+            }
+            k += 2;
+        }
+    }
+
+    merge_runs(backtrack(&trace, offset, n, m))
+}
+
+/// Walks the recorded `V` snapshots backwards from `(n, m)` to `(0, 0)`,
+/// emitting one-byte edits that `merge_runs` later coalesces into runs.
+fn backtrack(trace: &[Vec<i64>], offset: usize, n: i64, m: i64) -> Vec<Edit> {
+    let mut x = n;
+    let mut y = m;
+    let mut ops = Vec::new();
+
+    for d in (0..trace.len() as i64).rev() {
+        let v = &trace[d as usize];
+        let idx = |k: i64| (k + offset as i64) as usize;
+        let k = x - y;
+
+        let prev_k = if k == -d || (k != d && v[idx(k - 1)] < v[idx(k + 1)]) {
+            k + 1
+        } else {
+            k - 1
+        };
+        let prev_x = v[idx(prev_k)];
+        let prev_y = prev_x - prev_k;
+
+        while x > prev_x && y > prev_y {
+            ops.push(Edit::Equal { a: (x - 1) as usize, b: (y - 1) as usize, len: 1 });
+            x -= 1;
+            y -= 1;
+        }
+
+        if d > 0 {
+            if x == prev_x {
+                ops.push(Edit::Insert { a: x as usize, b: (y - 1) as usize, len: 1 });
+            } else {
+                ops.push(Edit::Delete { a: (x - 1) as usize, len: 1 });
+            }
+        }
+        x = prev_x;
+        y = prev_y;
+    }
+
+    ops.reverse();
+    ops
+}
+
+/// Coalesces the one-byte-at-a-time edits from `backtrack` into runs.
+fn merge_runs(ops: Vec<Edit>) -> Vec<Edit> {
+    let mut merged: Vec<Edit> = Vec::new();
+    for op in ops {
+        let mut appended = false;
+        if let Some(last) = merged.last_mut() {
+            appended = match (last, &op) {
+                (Edit::Equal { a, b, len }, Edit::Equal { a: na, b: nb, .. })
+                    if *a + *len == *na && *b + *len == *nb =>
+                {
+                    *len += 1;
+                    true
+                }
+                (Edit::Delete { a, len }, Edit::Delete { a: na, .. }) if *a + *len == *na => {
+                    *len += 1;
+                    true
+                }
+                (Edit::Insert { a, b, len }, Edit::Insert { a: na, b: nb, .. })
+                    if *a == *na && *b + *len == *nb =>
+                {
+                    *len += 1;
+                    true
+                }
+                _ => false,
+            };
+        }
+        if !appended {
+            merged.push(op);
+        }
+    }
+    merged
+}
+
+/// Turns an edit script into the patch sections `apply_patch` understands.
+/// Adjacent delete+insert runs become a single `Replace` section; lone
+/// inserts are anchored on a neighbouring source byte so every section still
+/// has a non-empty `search` pattern to match on.
+pub(crate) fn edits_to_sections(edits: &[Edit], a: &[u8], b: &[u8]) -> Vec<PatchSection> {
+    let mut sections = Vec::new();
+    let mut id = 0u32;
+    let mut i = 0;
+    while i < edits.len() {
+        match edits[i] {
+            Edit::Equal { .. } => i += 1,
+            Edit::Delete { a: da, len: dlen } => {
+                if let Some(&Edit::Insert { b: ib, len: ilen, .. }) = edits.get(i + 1) {
+                    id += 1;
+                    sections.push(PatchSection {
+                        id,
+                        start: da,
+                        end: da + dlen - 1,
+                        search: a[da..da + dlen].to_vec(),
+                        data: b[ib..ib + ilen].to_vec(),
+                        op: SectionOp::Replace,
+                    });
+                    i += 2;
+                } else {
+                    id += 1;
+                    sections.push(PatchSection {
+                        id,
+                        start: da,
+                        end: da + dlen - 1,
+                        search: a[da..da + dlen].to_vec(),
+                        data: Vec::new(),
+                        op: SectionOp::Delete,
+                    });
+                    i += 1;
+                }
+            }
+            Edit::Insert { a: anchor, b: ib, len: ilen } => {
+                // If the byte right before this insert is already the last
+                // byte covered by the previous section (no equal run between
+                // them), that byte isn't actually unchanged -- it belongs to
+                // the previous edit. Anchoring on it here would produce a
+                // section overlapping the previous one's search range, which
+                // `apply_sections`'s single cursor can't reconcile. Fold the
+                // inserted bytes into the previous section's output instead.
+                let folds_into_previous = anchor > 0
+                    && sections
+                        .last()
+                        .is_some_and(|s: &PatchSection| s.end == anchor - 1);
+                if folds_into_previous {
+                    sections
+                        .last_mut()
+                        .unwrap()
+                        .data
+                        .extend_from_slice(&b[ib..ib + ilen]);
+                    i += 1;
+                    continue;
+                }
+                id += 1;
+                let (search, data, anchor_pos) = if anchor > 0 {
+                    let before = a[anchor - 1];
+                    let mut data = vec![before];
+                    data.extend_from_slice(&b[ib..ib + ilen]);
+                    (vec![before], data, anchor - 1)
+                } else if !a.is_empty() {
+                    let after = a[0];
+                    let mut data = b[ib..ib + ilen].to_vec();
+                    data.push(after);
+                    (vec![after], data, 0)
+                } else {
+                    (Vec::new(), b[ib..ib + ilen].to_vec(), 0)
+                };
+                sections.push(PatchSection {
+                    id,
+                    start: anchor_pos,
+                    end: anchor_pos,
+                    search,
+                    data,
+                    op: SectionOp::Insert,
+                });
+                i += 1;
+            }
+        }
+    }
+    sections
+}
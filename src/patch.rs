@@ -1,9 +1,17 @@
 use serde::{Deserialize, Serialize};
 use hex_buffer_serde::{Hex, HexForm};
 
+use crate::hash::ContentHash;
+
 #[derive(Clone, Serialize, Deserialize, PartialEq, Debug, Default)]
 pub(crate) struct Patch {
     pub sections: Vec<PatchSection>,
+    /// Fingerprint of the source file this patch was built from.
+    #[serde(default)]
+    pub source_hash: ContentHash,
+    /// Full hash of the file produced by applying this patch.
+    #[serde(default)]
+    pub target_hash: u128,
 }
 
 #[derive(Clone, Serialize, Deserialize, PartialEq, Debug, Default)]
@@ -15,4 +23,17 @@ pub(crate) struct PatchSection {
     pub search: Vec<u8>,
     #[serde(with = "HexForm::<Vec<u8>>")]
     pub data: Vec<u8>,
+    /// What this section does to the source bytes it matches.
+    #[serde(default)]
+    pub op: SectionOp,
+}
+
+/// Distinguishes a byte-for-byte replacement from a section produced by the
+/// Myers diff engine to express an insertion or deletion.
+#[derive(Clone, Copy, Serialize, Deserialize, PartialEq, Debug, Default)]
+pub(crate) enum SectionOp {
+    #[default]
+    Replace,
+    Insert,
+    Delete,
 }